@@ -1,10 +1,17 @@
-use anyhow::{anyhow, Result};
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use anyhow::anyhow;
+use jsonwebtoken::{decode, Algorithm, DecodingKey};
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+#[cfg(feature = "axum")]
+mod extractor;
+#[cfg(feature = "axum")]
+pub use extractor::{DefaultClaims, GitHubClaimsRejection, GitHubOidcClaims, ParseTokenClaims};
+
 /// Represents a JSON Web Key (JWK) used for token validation.
 ///
 /// A JWK is a digital secure key used in secure web communications.
@@ -20,10 +27,19 @@ pub struct JWK {
     pub kid: String,
     /// Algorithm used with this key (e.g., "RS256")
     pub alg: Option<String>,
-    /// RSA public key modulus (base64url-encoded)
-    pub n: String,
-    /// RSA public key exponent (base64url-encoded)
-    pub e: String,
+    /// RSA public key modulus (base64url-encoded). Only present on `RSA` keys.
+    pub n: Option<String>,
+    /// RSA public key exponent (base64url-encoded). Only present on `RSA` keys.
+    pub e: Option<String>,
+    /// The curve the key is on (e.g. "P-256", "Ed25519"). Only present on
+    /// `EC`/`OKP` keys.
+    pub crv: Option<String>,
+    /// EC/OKP public key x-coordinate (base64url-encoded). Only present on
+    /// `EC`/`OKP` keys.
+    pub x: Option<String>,
+    /// EC public key y-coordinate (base64url-encoded). Only present on `EC`
+    /// keys.
+    pub y: Option<String>,
     /// X.509 certificate chain (optional)
     pub x5c: Option<Vec<String>>,
     /// X.509 certificate SHA-1 thumbprint (optional)
@@ -60,6 +76,7 @@ pub struct GithubJWKS {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GitHubClaims {
     /// The subject of the token (e.g the GitHub Actions runner ID).
+    #[serde(rename = "sub")]
     pub subject: String,
 
     /// The full name of the repository.
@@ -73,8 +90,169 @@ pub struct GitHubClaims {
 
     /// The timestamp when the token was issued.
     pub iat: u64,
+
+    /// The timestamp after which the token is no longer valid.
+    pub exp: u64,
+
+    /// The timestamp before which the token must not be accepted, if GitHub
+    /// set one.
+    pub nbf: Option<u64>,
+
+    /// Any other claims GitHub put on the token (`iss`, `aud`, `environment`,
+    /// `ref`, etc.) that don't have a dedicated field above.
+    ///
+    /// This is what lets [`Validation::Claim`] assert on arbitrary OIDC
+    /// claims without this crate needing to grow a new struct field for
+    /// every one GitHub ships.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+/// A single rule to check against a token's claims once its signature has
+/// been verified.
+///
+/// Rather than hardcoding a fixed policy (as `validate_github_token` used to,
+/// via the `GITHUB_ORG`/`GITHUB_REPO` env vars), validation is expressed as a
+/// list of composable rules, modeled on the `Validation` enum from the
+/// `alcoholic_jwt` crate. [`GithubJWKS::validate_github_token_with`] checks
+/// every rule and reports back exactly which ones the token failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Validation {
+    /// The `iss` claim must equal this value.
+    Issuer(String),
+    /// The `aud` claim must equal this value.
+    Audience(String),
+    /// The `sub` claim must be present and non-empty.
+    SubjectPresent,
+    /// The `repository_owner` claim must equal this value.
+    RepositoryOwner(String),
+    /// The `repository` claim must equal this value.
+    Repository(String),
+    /// A generic claim check: the claim named `name` must equal `expected`.
+    Claim { name: String, expected: String },
+    /// The token must not be expired (`exp`) or used before its `nbf`,
+    /// tolerating `leeway_seconds` of clock skew between this service and
+    /// GitHub's token issuer.
+    NotExpired { leeway_seconds: u64 },
+}
+
+/// The default clock-skew tolerance used by [`Validation::NotExpired`] when
+/// [`GithubJWKS::validate_github_token`] adds it automatically.
+pub const DEFAULT_LEEWAY_SECONDS: u64 = 60;
+
+impl Validation {
+    /// Reads a claim by name, whether it has a dedicated field on
+    /// [`GitHubClaims`] or only lives in [`GitHubClaims::extra`].
+    fn claim_str<'a>(claims: &'a GitHubClaims, name: &str) -> Option<&'a str> {
+        match name {
+            "sub" => Some(claims.subject.as_str()),
+            "repository" => Some(claims.repository.as_str()),
+            "repository_owner" => Some(claims.repository_owner.as_str()),
+            "job_workflow_ref" => Some(claims.job_workflow_ref.as_str()),
+            _ => claims.extra.get(name).and_then(|v| v.as_str()),
+        }
+    }
+
+    /// Checks this rule against a decoded token's claims.
+    fn is_satisfied_by(&self, claims: &GitHubClaims) -> bool {
+        match self {
+            Validation::Issuer(expected) => {
+                Self::claim_str(claims, "iss") == Some(expected.as_str())
+            }
+            Validation::Audience(expected) => {
+                Self::claim_str(claims, "aud") == Some(expected.as_str())
+            }
+            Validation::SubjectPresent => !claims.subject.is_empty(),
+            Validation::RepositoryOwner(expected) => claims.repository_owner == *expected,
+            Validation::Repository(expected) => claims.repository == *expected,
+            Validation::Claim { name, expected } => {
+                Self::claim_str(claims, name) == Some(expected.as_str())
+            }
+            Validation::NotExpired { leeway_seconds } => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|duration| duration.as_secs())
+                    .unwrap_or(0);
+
+                let not_yet_expired = claims.exp.saturating_add(*leeway_seconds) >= now;
+                let already_active = claims
+                    .nbf
+                    .map(|nbf| nbf <= now.saturating_add(*leeway_seconds))
+                    .unwrap_or(true);
+
+                not_yet_expired && already_active
+            }
+        }
+    }
+}
+
+/// Errors produced while validating a GitHub Actions OIDC token.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The token wasn't a well-formed JWT.
+    #[error("invalid token format. Expected a JWT.")]
+    InvalidTokenFormat,
+
+    /// The token's header couldn't be decoded.
+    #[error("failed to decode header: {0}. Make sure you're using a valid JWT, not a PAT.")]
+    HeaderDecode(#[source] jsonwebtoken::errors::Error),
+
+    /// The token header didn't have a `kid` at all. GitHub always sets one,
+    /// and there's no key we can safely validate against without it.
+    #[error("token header has no `kid`")]
+    MissingKid,
+
+    /// The token's `kid` didn't match any key in the JWKS.
+    #[error("matching key not found in JWKS")]
+    KeyNotFound,
+
+    /// The matching JWK couldn't be turned into a decoding key (missing
+    /// components, an unparseable `x5c` certificate, etc.).
+    #[error("failed to create decoding key: {0}")]
+    KeyConstruction(String),
+
+    /// The matching JWK's `kty`/`alg`/`crv` don't correspond to an algorithm
+    /// this crate knows how to verify.
+    #[error("unsupported key algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+
+    /// The token header's `alg` didn't match the algorithm implied by the
+    /// matching JWK. Accepting the token anyway would open the door to
+    /// algorithm-confusion attacks (e.g. an attacker presenting an RS256
+    /// token's public key as an HMAC secret).
+    #[error("token header alg {header:?} does not match the matching key's alg {jwk:?}")]
+    AlgorithmMismatch { header: Algorithm, jwk: Algorithm },
+
+    /// The token's signature (or a standard claim jsonwebtoken checks, like
+    /// shape) didn't verify.
+    #[error("failed to decode token: {0}")]
+    Decode(#[source] jsonwebtoken::errors::Error),
+
+    /// The token's `exp` or `nbf` claim failed [`Validation::NotExpired`].
+    ///
+    /// This gets its own variant, distinct from [`Error::ValidationFailed`],
+    /// so callers can tell "this token was otherwise valid but has expired"
+    /// apart from "this token failed a policy check" or "this token's
+    /// signature didn't verify".
+    ///
+    /// `TokenExpired` takes precedence over every other failed
+    /// [`Validation`]: if a token is both expired and, say, from the wrong
+    /// repository, [`GithubJWKS::validate_github_token_with`] returns only
+    /// this variant, and the other failed rules are not reported in
+    /// [`Error::ValidationFailed`]. An expired token should be rejected and
+    /// re-requested rather than debugged against policy, so losing the rest
+    /// of the failure detail in that case is an acceptable trade.
+    #[error("token is expired or not yet valid")]
+    TokenExpired,
+
+    /// One or more [`Validation`] rules rejected the token's claims.
+    #[error("token failed validation: {0:?}")]
+    ValidationFailed(Vec<Validation>),
 }
 
+/// A `Result` defaulting to this crate's [`Error`] type.
+pub type Result<T> = std::result::Result<T, Error>;
+
 /// Fetches the JSON Web Key Set (JWKS) from the specified OIDC URL.
 ///
 /// This function is used to retrieve the set of public keys that GitHub uses
@@ -94,7 +272,7 @@ pub struct GitHubClaims {
 /// ```
 /// let jwks = fetch_jwks(your_oidc_url).await?;
 /// ```
-pub async fn fetch_jwks(oidc_url: &str) -> Result<GithubJWKS> {
+pub async fn fetch_jwks(oidc_url: &str) -> anyhow::Result<GithubJWKS> {
     info!("Fetching JWKS from {}", oidc_url);
     let client = reqwest::Client::new();
     let jwks_url = format!("{}/.well-known/jwks", oidc_url);
@@ -116,81 +294,248 @@ pub async fn fetch_jwks(oidc_url: &str) -> Result<GithubJWKS> {
     }
 }
 
+/// Works out which [`Algorithm`] a JWK was meant to verify, and builds a
+/// matching [`DecodingKey`] for it.
+///
+/// Supports `RSA` keys (via their modulus/exponent), `EC` keys (via their
+/// curve coordinates, ES256 for `P-256` and ES384 for `P-384`), and `OKP`
+/// (`EdDSA`/Ed25519) keys, plus an `x5c` certificate chain as an alternative
+/// source of key material for `RSA`/`EC` keys. The resolved algorithm is
+/// compared against the token header's `alg` so a token can't claim to be
+/// signed with one algorithm while being verified as if it were another
+/// (algorithm-confusion attacks).
+fn decoding_key_and_algorithm(key: &JWK, header_alg: Algorithm) -> Result<(DecodingKey, Algorithm)> {
+    let alg = match key.alg.as_deref() {
+        Some("RS256") => Algorithm::RS256,
+        Some("RS384") => Algorithm::RS384,
+        Some("RS512") => Algorithm::RS512,
+        Some("ES256") => Algorithm::ES256,
+        Some("ES384") => Algorithm::ES384,
+        Some("EdDSA") => Algorithm::EdDSA,
+        Some(other) => return Err(Error::UnsupportedAlgorithm(other.to_string())),
+        None => match key.kty.as_str() {
+            "RSA" => Algorithm::RS256,
+            "EC" => match key.crv.as_deref() {
+                Some("P-384") => Algorithm::ES384,
+                _ => Algorithm::ES256,
+            },
+            "OKP" => Algorithm::EdDSA,
+            other => return Err(Error::UnsupportedAlgorithm(other.to_string())),
+        },
+    };
+
+    if alg != header_alg {
+        return Err(Error::AlgorithmMismatch {
+            header: header_alg,
+            jwk: alg,
+        });
+    }
+
+    let decoding_key = if let Some(leading_cert) = key.x5c.as_ref().and_then(|chain| chain.first()) {
+        decoding_key_from_certificate(leading_cert, alg)?
+    } else {
+        match key.kty.as_str() {
+            "RSA" => {
+                let n = key
+                    .n
+                    .as_deref()
+                    .ok_or_else(|| Error::KeyConstruction("RSA JWK is missing `n`".to_string()))?;
+                let e = key
+                    .e
+                    .as_deref()
+                    .ok_or_else(|| Error::KeyConstruction("RSA JWK is missing `e`".to_string()))?;
+
+                DecodingKey::from_rsa_components(n, e)
+                    .map_err(|e| Error::KeyConstruction(e.to_string()))?
+            }
+            "EC" => {
+                let x = key
+                    .x
+                    .as_deref()
+                    .ok_or_else(|| Error::KeyConstruction("EC JWK is missing `x`".to_string()))?;
+                let y = key
+                    .y
+                    .as_deref()
+                    .ok_or_else(|| Error::KeyConstruction("EC JWK is missing `y`".to_string()))?;
+
+                DecodingKey::from_ec_components(x, y)
+                    .map_err(|e| Error::KeyConstruction(e.to_string()))?
+            }
+            "OKP" => {
+                let x = key
+                    .x
+                    .as_deref()
+                    .ok_or_else(|| Error::KeyConstruction("OKP JWK is missing `x`".to_string()))?;
+
+                DecodingKey::from_ed_components(x).map_err(|e| Error::KeyConstruction(e.to_string()))?
+            }
+            other => {
+                return Err(Error::KeyConstruction(format!(
+                    "unsupported key type `{other}`"
+                )))
+            }
+        }
+    };
+
+    Ok((decoding_key, alg))
+}
+
+/// Builds a [`DecodingKey`] from the leading certificate of a JWK's `x5c`
+/// chain, as an alternative to raw key components.
+fn decoding_key_from_certificate(x5c_entry: &str, alg: Algorithm) -> Result<DecodingKey> {
+    use base64::Engine;
+
+    let der = base64::engine::general_purpose::STANDARD
+        .decode(x5c_entry)
+        .map_err(|e| Error::KeyConstruction(format!("invalid x5c certificate base64: {e}")))?;
+
+    let (_, certificate) = x509_parser::parse_x509_certificate(&der)
+        .map_err(|e| Error::KeyConstruction(format!("failed to parse x5c certificate: {e}")))?;
+
+    let public_key_pem = pem_from_der("PUBLIC KEY", certificate.tbs_certificate.subject_pki.raw);
+
+    match alg {
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {
+            DecodingKey::from_rsa_pem(public_key_pem.as_bytes())
+        }
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(public_key_pem.as_bytes()),
+        other => {
+            return Err(Error::KeyConstruction(format!(
+                "x5c certificates are only supported for RSA/EC algorithms, found {other:?}"
+            )))
+        }
+    }
+    .map_err(|e| Error::KeyConstruction(format!("failed to build decoding key from certificate: {e}")))
+}
+
+/// Wraps raw DER bytes in a PEM envelope with the given label.
+fn pem_from_der(label: &str, der: &[u8]) -> String {
+    use base64::Engine;
+
+    let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for chunk in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(chunk).expect("base64 output is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+/// Decodes a token's JOSE header, without verifying its signature.
+fn decode_header(token: &str) -> Result<jsonwebtoken::Header> {
+    jsonwebtoken::decode_header(token).map_err(Error::HeaderDecode)
+}
+
+/// Returns a token's `kid` header claim, without verifying its signature.
+///
+/// Decoding the header is documented (by e.g. `alcoholic_jwt`, which this
+/// crate's [`Validation`] design borrows from) as the only safe operation to
+/// perform on an untrusted token before full validation. Callers can use the
+/// `kid` to pick the right key set or environment, or to log which key a
+/// failed validation was for, without trusting anything else about the
+/// token's contents.
+pub fn token_kid(token: &str) -> Result<Option<String>> {
+    Ok(decode_header(token)?.kid)
+}
+
 impl GithubJWKS {
-    pub async fn validate_github_token(
+    /// Decodes and validates a GitHub Actions OIDC token, checking it
+    /// against every rule in `validations`.
+    ///
+    /// Every rule is checked (validation doesn't stop at the first failure),
+    /// so callers get back the complete list of what a token failed via
+    /// [`Error::ValidationFailed`] rather than just the first mismatch.
+    pub async fn validate_github_token_with(
         token: &str,
         jwks: Arc<RwLock<GithubJWKS>>,
-        expected_audience: Option<&str>,
+        validations: &[Validation],
     ) -> Result<GitHubClaims> {
         debug!("Starting token validation");
-        if !token.starts_with("eyJ") {
-            warn!("Invalid token format received");
-            return Err(anyhow!("Invalid token format. Expected a JWT."));
-        }
 
         let jwks = jwks.read().await;
         debug!("JWKS loaded");
 
-        let header = jsonwebtoken::decode_header(token).map_err(|e| {
-            anyhow!(
-                "Failed to decode header: {}. Make sure you're using a valid JWT, not a PAT.",
-                e
-            )
-        })?;
-
-        let decoding_key = if let Some(kid) = header.kid {
-            let key = jwks
-                .keys
-                .iter()
-                .find(|k| k.kid == kid)
-                .ok_or_else(|| anyhow!("Matching key not found in JWKS"))?;
-
-            let modulus = key.n.as_str();
-            let exponent = key.e.as_str();
-
-            DecodingKey::from_rsa_components(modulus, exponent)
-                .map_err(|e| anyhow!("Failed to create decoding key: {}", e))?
-        } else {
-            DecodingKey::from_secret("your_secret_key".as_ref())
-        };
+        let header = decode_header(token)?;
 
-        let mut validation = Validation::new(Algorithm::RS256);
-        if let Some(audience) = expected_audience {
-            validation.set_audience(&[audience]);
-        }
+        let kid = header.kid.as_deref().ok_or(Error::MissingKid)?;
+        let key = jwks
+            .keys
+            .iter()
+            .find(|k| k.kid == kid)
+            .ok_or(Error::KeyNotFound)?;
+
+        let (decoding_key, algorithm) = decoding_key_and_algorithm(key, header.alg)?;
+
+        // Standard checks like `aud`/`iss`/`exp` are driven by our own
+        // `Validation` rules below, so that a token can be checked against
+        // (and fail) several of them at once instead of jsonwebtoken bailing
+        // out on the first one.
+        let mut jwt_validation = jsonwebtoken::Validation::new(algorithm);
+        jwt_validation.validate_exp = false;
+        jwt_validation.required_spec_claims.clear();
 
-        let token_data = decode::<GitHubClaims>(token, &decoding_key, &validation)
-            .map_err(|e| anyhow!("Failed to decode token: {}", e))?;
+        let token_data =
+            decode::<GitHubClaims>(token, &decoding_key, &jwt_validation).map_err(Error::Decode)?;
 
         let claims = token_data.claims;
 
-        if let Ok(org) = std::env::var("GITHUB_ORG") {
-            if claims.repository_owner != org {
-                warn!(
-                    "Token organization mismatch. Expected: {}, Found: {}",
-                    org, claims.repository_owner
-                );
-                return Err(anyhow!("Token is not from the expected organization"));
-            }
+        let failed: Vec<Validation> = validations
+            .iter()
+            .filter(|validation| !validation.is_satisfied_by(&claims))
+            .cloned()
+            .collect();
+
+        if failed
+            .iter()
+            .any(|validation| matches!(validation, Validation::NotExpired { .. }))
+        {
+            warn!("Token is expired or not yet valid");
+            return Err(Error::TokenExpired);
         }
 
-        if let Ok(repo) = std::env::var("GITHUB_REPO") {
-            debug!(
-                "Comparing repositories - Expected: {}, Found: {}",
-                repo, claims.repository
-            );
-            if claims.repository != repo {
-                warn!(
-                    "Token repository mismatch. Expected: {}, Found: {}",
-                    repo, claims.repository
-                );
-                return Err(anyhow!("Token is not from the expected repository"));
-            }
+        if !failed.is_empty() {
+            warn!("Token failed validation rules: {:?}", failed);
+            return Err(Error::ValidationFailed(failed));
         }
 
         debug!("Token validation completed successfully");
         Ok(claims)
     }
+
+    /// Decodes and validates a GitHub Actions OIDC token, checking that it
+    /// isn't expired (with [`DEFAULT_LEEWAY_SECONDS`] of clock-skew leeway),
+    /// the audience (if given), and the `GITHUB_ORG`/`GITHUB_REPO`
+    /// environment variables (if set).
+    ///
+    /// This is kept for backwards compatibility; prefer
+    /// [`GithubJWKS::validate_github_token_with`] for new code, since it lets
+    /// you express this (and any other) policy directly as a list of
+    /// [`Validation`] rules instead of waiting on env vars or new struct
+    /// fields.
+    pub async fn validate_github_token(
+        token: &str,
+        jwks: Arc<RwLock<GithubJWKS>>,
+        expected_audience: Option<&str>,
+    ) -> Result<GitHubClaims> {
+        let mut validations = vec![Validation::NotExpired {
+            leeway_seconds: DEFAULT_LEEWAY_SECONDS,
+        }];
+
+        if let Some(audience) = expected_audience {
+            validations.push(Validation::Audience(audience.to_string()));
+        }
+
+        if let Ok(org) = std::env::var("GITHUB_ORG") {
+            validations.push(Validation::RepositoryOwner(org));
+        }
+
+        if let Ok(repo) = std::env::var("GITHUB_REPO") {
+            validations.push(Validation::Repository(repo));
+        }
+
+        Self::validate_github_token_with(token, jwks, &validations).await
+    }
 }
 
 pub async fn validate_github_token(
@@ -200,3 +545,599 @@ pub async fn validate_github_token(
 ) -> Result<GitHubClaims> {
     GithubJWKS::validate_github_token(token, jwks, expected_audience).await
 }
+
+/// How often a [`KeyManager`] re-fetches the JWKS in the background, unless
+/// a different interval is given to [`KeyManager::with_refresh_interval`].
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The minimum time a [`KeyManager`] will wait between two JWKS fetches,
+/// even if a refresh is forced (e.g. by an unrecognized `kid`). This keeps a
+/// burst of tokens signed with an unknown key from triggering a stampede of
+/// redundant fetches against the OIDC provider.
+const MIN_REFRESH_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Owns a JWKS and keeps it fresh in the background.
+///
+/// GitHub rotates its OIDC signing keys, and [`fetch_jwks`] on its own gives
+/// callers no story for picking up that rotation. `KeyManager` fetches the
+/// JWKS once up front, spawns a Tokio task that re-fetches it on an interval
+/// (following the "update on interval" approach from `axum-jwks`), and also
+/// triggers a one-off refresh whenever a token's `kid` isn't found in the
+/// cached set, so newly-rotated keys don't have to wait for the next tick.
+pub struct KeyManager {
+    oidc_url: String,
+    jwks: Arc<RwLock<GithubJWKS>>,
+    last_refresh: Arc<RwLock<Instant>>,
+}
+
+impl KeyManager {
+    /// Creates a `KeyManager`, fetching the JWKS once synchronously and then
+    /// refreshing it every [`DEFAULT_REFRESH_INTERVAL`] in the background.
+    pub async fn new(oidc_url: impl Into<String>) -> anyhow::Result<Self> {
+        Self::with_refresh_interval(oidc_url, DEFAULT_REFRESH_INTERVAL).await
+    }
+
+    /// Creates a `KeyManager` with a custom background refresh interval.
+    pub async fn with_refresh_interval(
+        oidc_url: impl Into<String>,
+        refresh_interval: Duration,
+    ) -> anyhow::Result<Self> {
+        let oidc_url = oidc_url.into();
+        let jwks = fetch_jwks(&oidc_url).await?;
+
+        let manager = Self {
+            oidc_url,
+            jwks: Arc::new(RwLock::new(jwks)),
+            last_refresh: Arc::new(RwLock::new(Instant::now())),
+        };
+
+        manager.spawn_refresh_task(refresh_interval);
+
+        Ok(manager)
+    }
+
+    fn spawn_refresh_task(&self, refresh_interval: Duration) {
+        let oidc_url = self.oidc_url.clone();
+        let jwks = self.jwks.clone();
+        let last_refresh = self.last_refresh.clone();
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(refresh_interval);
+            // The first tick fires immediately, and we already fetched the
+            // JWKS once in `with_refresh_interval`, so skip it.
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                if let Err(e) = Self::refresh(&oidc_url, &jwks, &last_refresh).await {
+                    error!("Background JWKS refresh failed: {:?}", e);
+                }
+            }
+        });
+    }
+
+    async fn refresh(
+        oidc_url: &str,
+        jwks: &Arc<RwLock<GithubJWKS>>,
+        last_refresh: &Arc<RwLock<Instant>>,
+    ) -> anyhow::Result<()> {
+        {
+            let mut last_refresh = last_refresh.write().await;
+            if last_refresh.elapsed() < MIN_REFRESH_COOLDOWN {
+                debug!("Skipping JWKS refresh; still within the cooldown window");
+                return Ok(());
+            }
+            *last_refresh = Instant::now();
+        }
+
+        let fresh = fetch_jwks(oidc_url).await?;
+        *jwks.write().await = fresh;
+        Ok(())
+    }
+
+    /// Forces an immediate JWKS refresh, bypassing the refresh interval
+    /// (though still subject to [`MIN_REFRESH_COOLDOWN`]).
+    pub async fn force_refresh(&self) -> anyhow::Result<()> {
+        Self::refresh(&self.oidc_url, &self.jwks, &self.last_refresh).await
+    }
+
+    /// Returns the shared, continuously-refreshed JWKS handle.
+    pub fn jwks(&self) -> Arc<RwLock<GithubJWKS>> {
+        self.jwks.clone()
+    }
+
+    /// Decodes and validates a GitHub Actions OIDC token against the
+    /// managed JWKS, checking it against every rule in `validations`.
+    ///
+    /// If the token's `kid` isn't present in the cached JWKS, this triggers
+    /// an immediate refresh and retries validation once before giving up,
+    /// so a key GitHub just rotated in doesn't fail every request until the
+    /// next background refresh.
+    pub async fn validate(&self, token: &str, validations: &[Validation]) -> Result<GitHubClaims> {
+        match GithubJWKS::validate_github_token_with(token, self.jwks(), validations).await {
+            Err(Error::KeyNotFound) => {
+                debug!("kid not found in cached JWKS; refreshing and retrying once");
+                if let Err(e) = self.force_refresh().await {
+                    warn!("Failed to refresh JWKS after an unrecognized kid: {:?}", e);
+                    return Err(Error::KeyNotFound);
+                }
+
+                GithubJWKS::validate_github_token_with(token, self.jwks(), validations).await
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims_with(exp: u64, nbf: Option<u64>) -> GitHubClaims {
+        GitHubClaims {
+            subject: "repo:octocat/hello-world:ref:refs/heads/main".to_string(),
+            repository: "octocat/hello-world".to_string(),
+            repository_owner: "octocat".to_string(),
+            job_workflow_ref: "octocat/hello-world/.github/workflows/ci.yml@refs/heads/main"
+                .to_string(),
+            iat: exp.saturating_sub(60),
+            exp,
+            nbf,
+            extra: HashMap::new(),
+        }
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("system clock is after the epoch")
+            .as_secs()
+    }
+
+    /// An empty JWK of the given `kty`, for tests to fill in just the
+    /// fields they care about.
+    fn base_jwk(kty: &str) -> JWK {
+        JWK {
+            kty: kty.to_string(),
+            use_: None,
+            kid: "test-key".to_string(),
+            alg: None,
+            n: None,
+            e: None,
+            crv: None,
+            x: None,
+            y: None,
+            x5c: None,
+            x5t: None,
+            x5t_s256: None,
+        }
+    }
+
+    // RFC 7517 appendix A.1's example RSA public key.
+    const RSA_N: &str = "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw";
+    const RSA_E: &str = "AQAB";
+
+    // RFC 7515 appendix A.3's example ES256 public key coordinates.
+    const EC_P256_X: &str = "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU";
+    const EC_P256_Y: &str = "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0";
+
+    // RFC 8037 appendix A.2's example Ed25519 public key.
+    const OKP_ED25519_X: &str = "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo";
+
+    // A throwaway, self-signed P-256 certificate generated for this test
+    // suite (`openssl req -new -x509 -key <p256 key> -days 3650`). Its
+    // private key isn't used anywhere and was discarded after generation.
+    const EC_P256_X5C: &str = "MIIBdDCCARmgAwIBAgIUBDbS4MvsufRDRlNS0Zy1V33qGcowCgYIKoZIzj0EAwIwDzENMAsGA1UEAwwEdGVzdDAeFw0yNjA3MjkyMDQwMDBaFw0zNjA3MjYyMDQwMDBaMA8xDTALBgNVBAMMBHRlc3QwWTATBgcqhkjOPQIBBggqhkjOPQMBBwNCAASqziu40Bq5uwRXmLrIqzsknbfu+0UNDtrHGQ73T6PqbeJn9mwBqGsfTG58wI41eiN6LspTWiVG+AArVwdAbMDeo1MwUTAdBgNVHQ4EFgQUbNeuqSlXIKfpZOU86aAgleWLJs8wHwYDVR0jBBgwFoAUbNeuqSlXIKfpZOU86aAgleWLJs8wDwYDVR0TAQH/BAUwAwEB/zAKBggqhkjOPQQDAgNJADBGAiEAuNQadM/cVIXqUiuQsgAD7fXhpIUvN9wux+Vm+kUqZkQCIQD97PB5W82HbC+Pj05NYlR4DQVRNh8VqNVTuydsuFEyrA==";
+
+    #[test]
+    fn decoding_key_and_algorithm_rejects_header_alg_mismatch() {
+        let key = JWK {
+            alg: Some("RS256".to_string()),
+            n: Some(RSA_N.to_string()),
+            e: Some(RSA_E.to_string()),
+            ..base_jwk("RSA")
+        };
+
+        let result = decoding_key_and_algorithm(&key, Algorithm::ES256);
+
+        assert!(matches!(
+            result,
+            Err(Error::AlgorithmMismatch {
+                header: Algorithm::ES256,
+                jwk: Algorithm::RS256,
+            })
+        ));
+    }
+
+    #[test]
+    fn decoding_key_and_algorithm_accepts_a_matching_rsa_key() {
+        let key = JWK {
+            alg: Some("RS256".to_string()),
+            n: Some(RSA_N.to_string()),
+            e: Some(RSA_E.to_string()),
+            ..base_jwk("RSA")
+        };
+
+        let (_, alg) = decoding_key_and_algorithm(&key, Algorithm::RS256)
+            .expect("a well-formed RSA JWK should produce a decoding key");
+        assert_eq!(alg, Algorithm::RS256);
+    }
+
+    #[test]
+    fn decoding_key_and_algorithm_builds_an_ec_key_from_coordinates() {
+        let key = JWK {
+            crv: Some("P-256".to_string()),
+            x: Some(EC_P256_X.to_string()),
+            y: Some(EC_P256_Y.to_string()),
+            ..base_jwk("EC")
+        };
+
+        let (_, alg) = decoding_key_and_algorithm(&key, Algorithm::ES256)
+            .expect("a well-formed EC JWK should produce a decoding key");
+        assert_eq!(alg, Algorithm::ES256);
+    }
+
+    #[test]
+    fn decoding_key_and_algorithm_rejects_an_ec_key_missing_y() {
+        let key = JWK {
+            crv: Some("P-256".to_string()),
+            x: Some(EC_P256_X.to_string()),
+            ..base_jwk("EC")
+        };
+
+        let result = decoding_key_and_algorithm(&key, Algorithm::ES256);
+        assert!(matches!(result, Err(Error::KeyConstruction(_))));
+    }
+
+    #[test]
+    fn decoding_key_and_algorithm_builds_an_okp_key_from_x() {
+        let key = JWK {
+            crv: Some("Ed25519".to_string()),
+            x: Some(OKP_ED25519_X.to_string()),
+            ..base_jwk("OKP")
+        };
+
+        let (_, alg) = decoding_key_and_algorithm(&key, Algorithm::EdDSA)
+            .expect("a well-formed OKP JWK should produce a decoding key");
+        assert_eq!(alg, Algorithm::EdDSA);
+    }
+
+    #[test]
+    fn decoding_key_and_algorithm_builds_a_key_from_an_x5c_certificate() {
+        let key = JWK {
+            crv: Some("P-256".to_string()),
+            x5c: Some(vec![EC_P256_X5C.to_string()]),
+            ..base_jwk("EC")
+        };
+
+        let (_, alg) = decoding_key_and_algorithm(&key, Algorithm::ES256)
+            .expect("a valid x5c certificate should produce a decoding key");
+        assert_eq!(alg, Algorithm::ES256);
+    }
+
+    #[test]
+    fn decoding_key_and_algorithm_rejects_invalid_x5c_base64() {
+        let key = JWK {
+            crv: Some("P-256".to_string()),
+            x5c: Some(vec!["not valid base64!!".to_string()]),
+            ..base_jwk("EC")
+        };
+
+        let result = decoding_key_and_algorithm(&key, Algorithm::ES256);
+        assert!(matches!(result, Err(Error::KeyConstruction(_))));
+    }
+
+    #[tokio::test]
+    async fn validate_github_token_with_rejects_a_kidless_hmac_token() {
+        use base64::Engine;
+
+        // A header with no `kid`, claiming HS256, used to be validated
+        // against a hardcoded `DecodingKey::from_secret`. There's no
+        // legitimate key to check a kid-less token against, so this must be
+        // rejected outright rather than falling back to a shared secret.
+        let header_b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let claims = claims_with(now() + 3600, None);
+        let payload_b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(serde_json::to_vec(&claims).expect("claims serialize"));
+        let token = format!("{header_b64}.{payload_b64}.forged-signature");
+
+        let jwks = Arc::new(RwLock::new(GithubJWKS { keys: vec![] }));
+
+        let result = GithubJWKS::validate_github_token_with(&token, jwks, &[]).await;
+
+        assert!(matches!(result, Err(Error::MissingKid)));
+    }
+
+    #[test]
+    fn issuer_accepts_a_matching_iss_in_extra() {
+        let mut claims = claims_with(now() + 3600, None);
+        claims
+            .extra
+            .insert("iss".to_string(), serde_json::json!("https://token.actions.githubusercontent.com"));
+        let rule = Validation::Issuer("https://token.actions.githubusercontent.com".to_string());
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn issuer_rejects_a_mismatched_iss() {
+        let mut claims = claims_with(now() + 3600, None);
+        claims
+            .extra
+            .insert("iss".to_string(), serde_json::json!("https://evil.example.com"));
+        let rule = Validation::Issuer("https://token.actions.githubusercontent.com".to_string());
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn issuer_rejects_a_missing_iss() {
+        let claims = claims_with(now() + 3600, None);
+        let rule = Validation::Issuer("https://token.actions.githubusercontent.com".to_string());
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn audience_accepts_a_matching_aud_in_extra() {
+        let mut claims = claims_with(now() + 3600, None);
+        claims
+            .extra
+            .insert("aud".to_string(), serde_json::json!("https://my-service.example.com"));
+        let rule = Validation::Audience("https://my-service.example.com".to_string());
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn audience_rejects_a_mismatched_aud() {
+        let mut claims = claims_with(now() + 3600, None);
+        claims
+            .extra
+            .insert("aud".to_string(), serde_json::json!("https://someone-else.example.com"));
+        let rule = Validation::Audience("https://my-service.example.com".to_string());
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn subject_present_accepts_a_non_empty_subject() {
+        let claims = claims_with(now() + 3600, None);
+        assert!(Validation::SubjectPresent.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn subject_present_rejects_an_empty_subject() {
+        let mut claims = claims_with(now() + 3600, None);
+        claims.subject = String::new();
+        assert!(!Validation::SubjectPresent.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn repository_owner_accepts_a_matching_owner() {
+        let claims = claims_with(now() + 3600, None);
+        let rule = Validation::RepositoryOwner("octocat".to_string());
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn repository_owner_rejects_a_mismatched_owner() {
+        let claims = claims_with(now() + 3600, None);
+        let rule = Validation::RepositoryOwner("someone-else".to_string());
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn repository_accepts_a_matching_repository() {
+        let claims = claims_with(now() + 3600, None);
+        let rule = Validation::Repository("octocat/hello-world".to_string());
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn repository_rejects_a_mismatched_repository() {
+        let claims = claims_with(now() + 3600, None);
+        let rule = Validation::Repository("octocat/other-repo".to_string());
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn claim_accepts_a_matching_dedicated_field() {
+        let claims = claims_with(now() + 3600, None);
+        let rule = Validation::Claim {
+            name: "repository_owner".to_string(),
+            expected: "octocat".to_string(),
+        };
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn claim_accepts_a_matching_claim_that_only_lives_in_extra() {
+        let mut claims = claims_with(now() + 3600, None);
+        claims
+            .extra
+            .insert("environment".to_string(), serde_json::json!("production"));
+        let rule = Validation::Claim {
+            name: "environment".to_string(),
+            expected: "production".to_string(),
+        };
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn claim_rejects_a_claim_missing_from_both_dedicated_fields_and_extra() {
+        let claims = claims_with(now() + 3600, None);
+        let rule = Validation::Claim {
+            name: "ref".to_string(),
+            expected: "refs/heads/main".to_string(),
+        };
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn claim_str_prefers_the_dedicated_field_over_extra() {
+        let mut claims = claims_with(now() + 3600, None);
+        claims
+            .extra
+            .insert("repository".to_string(), serde_json::json!("shadowed/by-extra"));
+        assert_eq!(
+            Validation::claim_str(&claims, "repository"),
+            Some("octocat/hello-world")
+        );
+    }
+
+    #[test]
+    fn not_expired_accepts_a_token_within_its_exp() {
+        let claims = claims_with(now() + 60, None);
+        let rule = Validation::NotExpired { leeway_seconds: 0 };
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn not_expired_rejects_an_expired_token() {
+        let claims = claims_with(now() - 60, None);
+        let rule = Validation::NotExpired { leeway_seconds: 0 };
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn not_expired_tolerates_expiry_within_leeway() {
+        let claims = claims_with(now() - 10, None);
+        let rule = Validation::NotExpired {
+            leeway_seconds: 30,
+        };
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn not_expired_rejects_a_token_not_yet_valid() {
+        let claims = claims_with(now() + 3600, Some(now() + 60));
+        let rule = Validation::NotExpired { leeway_seconds: 0 };
+        assert!(!rule.is_satisfied_by(&claims));
+    }
+
+    #[test]
+    fn not_expired_tolerates_nbf_within_leeway() {
+        let claims = claims_with(now() + 3600, Some(now() + 10));
+        let rule = Validation::NotExpired {
+            leeway_seconds: 30,
+        };
+        assert!(rule.is_satisfied_by(&claims));
+    }
+
+    /// A bare-bones HTTP server that serves canned JWKS bodies off a local
+    /// TCP port, so `KeyManager` can be pointed at it in place of a real OIDC
+    /// provider. It ignores everything about the request but the fact that
+    /// one was made, which is all `fetch_jwks` needs and all these tests
+    /// care about.
+    ///
+    /// Returns the server's base URL and a counter of how many requests it
+    /// has served, so tests can assert on exactly how many times
+    /// `KeyManager` fetched the JWKS.
+    async fn spawn_fake_jwks_server(bodies: Vec<String>) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("binding a local test TCP listener should succeed");
+        let addr = listener.local_addr().expect("listener has a local address");
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let hits_for_task = hits.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    break;
+                };
+                let bodies = bodies.clone();
+                let hits = hits_for_task.clone();
+
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    // We don't care what was requested, only that something was.
+                    let _ = socket.read(&mut buf).await;
+
+                    let index = hits.fetch_add(1, Ordering::SeqCst);
+                    let body = bodies
+                        .get(index)
+                        .or_else(|| bodies.last())
+                        .cloned()
+                        .unwrap_or_default();
+
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+
+        (format!("http://{addr}"), hits)
+    }
+
+    /// A syntactically valid JWT with the given `kid` in its header. Its
+    /// payload and signature are placeholders: every test that uses this
+    /// fails validation at the "look up `kid` in the JWKS" step, before the
+    /// signature or payload is ever inspected.
+    fn token_with_kid(kid: &str) -> String {
+        use base64::Engine;
+
+        let header_b64 = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .encode(format!(r#"{{"alg":"RS256","kid":"{kid}","typ":"JWT"}}"#));
+        format!("{header_b64}.e30.cGxhY2Vob2xkZXI")
+    }
+
+    #[tokio::test]
+    async fn key_manager_refreshes_and_retries_once_on_an_unknown_kid() {
+        let (url, hits) = spawn_fake_jwks_server(vec![
+            r#"{"keys":[]}"#.to_string(),
+            r#"{"keys":[]}"#.to_string(),
+        ])
+        .await;
+
+        let manager = KeyManager::with_refresh_interval(url, Duration::from_secs(3600))
+            .await
+            .expect("the initial JWKS fetch should succeed");
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let result = manager.validate(&token_with_kid("missing-kid"), &[]).await;
+
+        assert!(matches!(result, Err(Error::KeyNotFound)));
+        assert_eq!(
+            hits.load(std::sync::atomic::Ordering::SeqCst),
+            2,
+            "an unknown kid should trigger exactly one refresh"
+        );
+    }
+
+    #[tokio::test]
+    async fn key_manager_does_not_refresh_again_within_the_cooldown() {
+        let (url, hits) = spawn_fake_jwks_server(vec![
+            r#"{"keys":[]}"#.to_string(),
+            r#"{"keys":[]}"#.to_string(),
+            r#"{"keys":[]}"#.to_string(),
+        ])
+        .await;
+
+        let manager = KeyManager::with_refresh_interval(url, Duration::from_secs(3600))
+            .await
+            .expect("the initial JWKS fetch should succeed");
+
+        let token = token_with_kid("still-missing");
+
+        let first = manager.validate(&token, &[]).await;
+        assert!(matches!(first, Err(Error::KeyNotFound)));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // A second miss, moments later, lands inside MIN_REFRESH_COOLDOWN and
+        // must not trigger a second fetch.
+        let second = manager.validate(&token, &[]).await;
+        assert!(matches!(second, Err(Error::KeyNotFound)));
+        assert_eq!(
+            hits.load(std::sync::atomic::Ordering::SeqCst),
+            2,
+            "a second miss within the cooldown should not trigger another fetch"
+        );
+    }
+}